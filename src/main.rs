@@ -1,13 +1,81 @@
-use std::{collections::HashMap, error::Error, num::NonZeroUsize};
+use std::{collections::{HashMap, HashSet}, error::Error, num::NonZeroUsize, sync::Arc, time::Duration};
+use chrono::{DateTime, Utc};
 use regex::Regex;
-use reqwest::{header::{HeaderMap, HeaderName, HeaderValue, ACCEPT}, Client, StatusCode, Url};
+use reqwest::{header::{HeaderMap, HeaderName, HeaderValue, ACCEPT, LINK, RETRY_AFTER}, Client, RequestBuilder, Response, StatusCode, Url};
 use semver::Version;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use clap::Parser;
-use tokio::task::JoinSet;
+use tokio::{sync::Semaphore, task::JoinSet};
 
 pub type BoxError = Box<dyn Error + Send + Sync>;
 
+/// Per-repository task result: the human-readable log lines, the structured records (always
+/// collected; `--format` decides which of the two gets printed), and the number of tags found
+/// eligible for deletion.
+type RepoResult = Result<(Vec<String>, Vec<Record>, usize), BoxError>;
+
+/// Base delay for exponential backoff between retries, doubled on each attempt.
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(200);
+
+/// Cap on the exponent passed to `2u32.pow` when computing backoff delay, so a large
+/// `--max-retries` can't overflow `u32` (debug panic / release wraparound). `2^20` base delays
+/// is already days long, far past anything a caller would sanely wait on.
+const MAX_BACKOFF_EXPONENT: u32 = 20;
+
+/// Everything needed to talk to the registry, bundled up since every call site needs all of it.
+#[derive(Debug, Clone)]
+pub struct RegistryClient {
+    client: Client,
+    registry_url: Url,
+    headers: HeaderMap,
+    auth: Option<(String, Option<String>)>,
+    semaphore: Arc<Semaphore>,
+    max_retries: u32,
+}
+
+/// Send a request built fresh by `build_request` on every attempt, retrying on connection
+/// errors and `429`/`5xx` responses up to `max_retries` times with exponential backoff,
+/// honoring the `Retry-After` header when the server sends one. A `Semaphore` permit is held
+/// for the whole call (including retries) so it also bounds how many requests - across every
+/// repository being processed - are ever in flight at once.
+async fn send_with_retry(
+    semaphore: &Semaphore,
+    max_retries: u32,
+    mut build_request: impl FnMut() -> RequestBuilder,
+) -> Result<Response, BoxError> {
+    let _permit = semaphore.acquire().await?;
+
+    let mut attempt = 0;
+    loop {
+        match build_request().send().await {
+            Ok(response) => {
+                let status = response.status();
+
+                if attempt < max_retries && (status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()) {
+                    let delay = response
+                        .headers()
+                        .get(RETRY_AFTER)
+                        .and_then(|v| v.to_str().ok())
+                        .and_then(|v| v.parse::<u64>().ok())
+                        .map(Duration::from_secs)
+                        .unwrap_or_else(|| RETRY_BASE_DELAY * 2u32.pow(attempt.min(MAX_BACKOFF_EXPONENT)));
+
+                    attempt += 1;
+                    tokio::time::sleep(delay).await;
+                    continue;
+                }
+
+                return Ok(response);
+            }
+            Err(e) if attempt < max_retries && (e.is_connect() || e.is_timeout()) => {
+                attempt += 1;
+                tokio::time::sleep(RETRY_BASE_DELAY * 2u32.pow((attempt - 1).min(MAX_BACKOFF_EXPONENT))).await;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct Catalog {
     repositories: Vec<String>,
@@ -19,6 +87,99 @@ pub struct ImageTagList {
     tags: Vec<String>,
 }
 
+/// Schema2/OCI manifest, just enough of it to find the config blob.
+#[derive(Debug, Clone, Deserialize)]
+struct ManifestV2 {
+    config: Option<ManifestV2Config>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct ManifestV2Config {
+    digest: String,
+}
+
+/// Image-config blob, just enough of it to read the creation timestamp.
+#[derive(Debug, Clone, Deserialize)]
+struct ImageConfig {
+    created: Option<String>,
+}
+
+/// Legacy schema1 manifest, used as a fallback when there's no config descriptor.
+#[derive(Debug, Clone, Deserialize)]
+struct ManifestV1 {
+    history: Option<Vec<ManifestV1History>>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct ManifestV1History {
+    #[serde(rename = "v1Compatibility")]
+    v1_compatibility: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct V1Compatibility {
+    created: Option<String>,
+}
+
+/// What happened (or would happen) to a single candidate tag.
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Keep,
+    Delete,
+    Skipped,
+    Protected,
+}
+
+/// One structured record per candidate tag, emitted by `--format json`/`ndjson` instead of the
+/// free-text log lines.
+#[derive(Debug, Clone, Serialize)]
+pub struct Record {
+    repository: String,
+    pattern: String,
+    tag: String,
+    digest: Option<String>,
+    action: Action,
+    reason: String,
+    deleted: bool,
+}
+
+/// Final counts emitted alongside the records in `--format json`/`ndjson`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Summary {
+    total_tags_to_delete: usize,
+    delete: bool,
+    errors: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct Report {
+    records: Vec<Record>,
+    summary: Summary,
+}
+
+/// How the deletion plan should be printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// Human-readable prose, one line per event.
+    Text,
+    /// A single JSON object with all records and a final summary.
+    Json,
+    /// One JSON record per line, followed by one JSON summary line.
+    Ndjson,
+}
+
+/// How tags should be ordered before `--max-per-tag` is applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum SortMode {
+    /// Order by semantic version, dropping a leading `v` if present.
+    Semver,
+    /// Order lexicographically.
+    Lex,
+    /// Order by manifest creation time (requires an extra fetch per tag).
+    Date,
+}
+
 /// Mark things for deletion, you'll have to run the garbage collection yourself
 #[derive(Debug, Clone, Parser)]
 #[command(version)]
@@ -43,69 +204,124 @@ pub struct Args {
     /// Regex for image whitelist, multiple can be specified if any of them match then it's in whitelist. If none all images are whitelisted
     #[arg(short, long)]
     images: Vec<String>,
-    // TODO: Maybe an enum for things? Semver vs regex tags somewhat contradict each other if we
-    // can't extract semver from the context.
-    /// Should the tags be sorted by semver?
-    #[arg(short,long)]
-    semver: bool,
+    /// Regex for tags to always keep, regardless of --max-per-tag or --min-age. Can be
+    /// specified multiple times; a tag matching any of them is never looked up for deletion.
+    #[arg(long = "exclude-tag")]
+    exclude_tags: Vec<String>,
+    /// How to order tags before applying --max-per-tag.
+    #[arg(short, long, value_enum, default_value_t = SortMode::Lex)]
+    sort: SortMode,
+    /// Tags whose manifest was created more recently than this are never deleted, regardless of
+    /// --max-per-tag. Accepts humantime durations, e.g. `12h`, `7d`, `2w`.
+    #[arg(long, visible_alias = "keep-newer-than")]
+    min_age: Option<humantime::Duration>,
+    /// Page size to request (`?n=`) when listing repositories/tags. Registries paginate these
+    /// listings on their own terms regardless, so this is only a hint.
+    #[arg(long)]
+    page_size: Option<NonZeroUsize>,
+    /// Maximum number of HTTP requests in flight at once, across every repository.
+    #[arg(long, default_value = "8")]
+    concurrency: NonZeroUsize,
+    /// Maximum number of retries for a request that fails with a connection error or a
+    /// 429/5xx response, using exponential backoff (or `Retry-After` when the server sends one).
+    /// The backoff delay itself is capped regardless of how high this is set.
+    #[arg(long, default_value = "3")]
+    max_retries: u32,
     /// Run actual deletions. Otherwise it's dry-run by default
     #[arg(short, long)]
     delete: bool,
+    /// How to print the deletion plan. `json`/`ndjson` are meant for piping into other tools.
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
 }
 
-pub async fn get_catalogs(
-    client: &Client,
-    registry_url: &Url,
-    headers: &HeaderMap,
-    auth: Option<&(String, Option<String>)>
-) -> Result<Catalog, BoxError> {
-    let mut catalog_request = client.get(registry_url.join("/v2/_catalog")?)
-        .headers(headers.clone());
-
-    if let Some(auth) = auth {
-        catalog_request = catalog_request.basic_auth(&auth.0, auth.1.as_ref());
-    }
+/// Follow the `Link: <...>; rel="next"` header conformant registries send when a listing is
+/// paginated, resolving a relative target against `base`.
+fn parse_next_link(headers: &HeaderMap, base: &Url) -> Option<Url> {
+    let link = headers.get(LINK)?.to_str().ok()?;
 
-    let catalog_response = catalog_request.send().await?.error_for_status()?;
+    link.split(',').find_map(|part| {
+        let mut segments = part.split(';');
+        let target = segments.next()?.trim();
 
-    Ok(catalog_response.json().await?)
+        if !segments.any(|attr| attr.trim() == "rel=\"next\"") {
+            return None;
+        }
+
+        base.join(target.trim_start_matches('<').trim_end_matches('>')).ok()
+    })
 }
 
-pub async fn get_tag_list(
-    client: &Client,
-    registry_url: &Url,
-    headers: &HeaderMap,
-    auth: Option<&(String, Option<String>)>,
-    repository: &str,
-) -> Result<ImageTagList, BoxError> {
-    let mut tag_list_request = client.get(registry_url.join(&format!("/v2/{repository}/tags/list"))?)
-        .headers(headers.clone());
-
-    if let Some(auth) = auth {
-        tag_list_request = tag_list_request.basic_auth(&auth.0, auth.1.as_ref());
+pub async fn get_catalogs(registry: &RegistryClient, page_size: Option<NonZeroUsize>) -> Result<Catalog, BoxError> {
+    let mut url = registry.registry_url.join("/v2/_catalog")?;
+    if let Some(page_size) = page_size {
+        url.query_pairs_mut().append_pair("n", &page_size.to_string());
     }
 
-    let tag_list_response = tag_list_request.send().await?.error_for_status()?;
+    let mut repositories = Vec::new();
+    let mut next_url = Some(url);
+
+    while let Some(url) = next_url {
+        let response = send_with_retry(&registry.semaphore, registry.max_retries, || {
+            let mut catalog_request = registry.client.get(url.clone()).headers(registry.headers.clone());
+
+            if let Some(auth) = &registry.auth {
+                catalog_request = catalog_request.basic_auth(&auth.0, auth.1.as_ref());
+            }
+
+            catalog_request
+        }).await?.error_for_status()?;
+
+        next_url = parse_next_link(response.headers(), &registry.registry_url);
+
+        let page: Catalog = response.json().await?;
+        repositories.extend(page.repositories);
+    }
 
-    Ok(tag_list_response.json().await?)
+    Ok(Catalog { repositories })
 }
 
-pub async fn get_tag_digest(
-    client: &Client,
-    registry_url: &Url,
-    headers: &HeaderMap,
-    auth: Option<&(String, Option<String>)>,
-    repository: &str,
-    tag: &str,
-) -> Result<Option<String>, BoxError> {
-    let mut tag_digest_request = client.head(registry_url.join(&format!("/v2/{repository}/manifests/{tag}"))?)
-        .headers(headers.clone());
-
-    if let Some(auth) = auth {
-        tag_digest_request = tag_digest_request.basic_auth(&auth.0, auth.1.as_ref());
+pub async fn get_tag_list(registry: &RegistryClient, repository: &str, page_size: Option<NonZeroUsize>) -> Result<ImageTagList, BoxError> {
+    let mut url = registry.registry_url.join(&format!("/v2/{repository}/tags/list"))?;
+    if let Some(page_size) = page_size {
+        url.query_pairs_mut().append_pair("n", &page_size.to_string());
     }
 
-    let tag_digest_response = tag_digest_request.send().await?;
+    let mut tags = Vec::new();
+    let mut next_url = Some(url);
+
+    while let Some(url) = next_url {
+        let response = send_with_retry(&registry.semaphore, registry.max_retries, || {
+            let mut tag_list_request = registry.client.get(url.clone()).headers(registry.headers.clone());
+
+            if let Some(auth) = &registry.auth {
+                tag_list_request = tag_list_request.basic_auth(&auth.0, auth.1.as_ref());
+            }
+
+            tag_list_request
+        }).await?.error_for_status()?;
+
+        next_url = parse_next_link(response.headers(), &registry.registry_url);
+
+        let page: ImageTagList = response.json().await?;
+        tags.extend(page.tags);
+    }
+
+    Ok(ImageTagList { tags })
+}
+
+pub async fn get_tag_digest(registry: &RegistryClient, repository: &str, tag: &str) -> Result<Option<String>, BoxError> {
+    let url = registry.registry_url.join(&format!("/v2/{repository}/manifests/{tag}"))?;
+
+    let tag_digest_response = send_with_retry(&registry.semaphore, registry.max_retries, || {
+        let mut tag_digest_request = registry.client.head(url.clone()).headers(registry.headers.clone());
+
+        if let Some(auth) = &registry.auth {
+            tag_digest_request = tag_digest_request.basic_auth(&auth.0, auth.1.as_ref());
+        }
+
+        tag_digest_request
+    }).await?;
 
     if tag_digest_response.status() == StatusCode::NOT_FOUND {
         return Ok(None);
@@ -123,47 +339,128 @@ pub async fn get_tag_digest(
     Ok(tag_digest)
 }
 
-pub async fn delete_tag(
-    client: &Client,
-    registry_url: &Url,
-    headers: &HeaderMap,
-    auth: Option<&(String, Option<String>)>,
-    repository: &str,
-    digest: &str,
-) -> Result<(), BoxError> {
-    let mut tag_delete_request = client.delete(registry_url.join(&format!("/v2/{repository}/manifests/{digest}"))?)
-        .headers(headers.clone());
-
-    if let Some(auth) = auth {
-        tag_delete_request = tag_delete_request.basic_auth(&auth.0, auth.1.as_ref());
-    }
+pub async fn delete_tag(registry: &RegistryClient, repository: &str, digest: &str) -> Result<(), BoxError> {
+    let url = registry.registry_url.join(&format!("/v2/{repository}/manifests/{digest}"))?;
+
+    send_with_retry(&registry.semaphore, registry.max_retries, || {
+        let mut tag_delete_request = registry.client.delete(url.clone()).headers(registry.headers.clone());
+
+        if let Some(auth) = &registry.auth {
+            tag_delete_request = tag_delete_request.basic_auth(&auth.0, auth.1.as_ref());
+        }
 
-    tag_delete_request.send().await?.error_for_status()?;
+        tag_delete_request
+    }).await?.error_for_status()?;
 
     Ok(())
 }
 
+/// Resolve the creation timestamp of a tag's manifest.
+///
+/// For schema2/OCI manifests this reads the config descriptor's digest and parses the
+/// top-level `created` field of the referenced image-config blob. Older registries (or
+/// images built before buildkit) may only serve a schema1 manifest with no config
+/// descriptor; in that case we fall back to the `history[].v1Compatibility` entries,
+/// each of which embeds its own `created` field, and take the most recent one.
+pub async fn get_tag_created(registry: &RegistryClient, repository: &str, tag: &str) -> Result<Option<DateTime<Utc>>, BoxError> {
+    let manifest_url = registry.registry_url.join(&format!("/v2/{repository}/manifests/{tag}"))?;
+
+    let manifest: ManifestV2 = send_with_retry(&registry.semaphore, registry.max_retries, || {
+        let mut manifest_request = registry.client.get(manifest_url.clone()).headers(registry.headers.clone());
+
+        if let Some(auth) = &registry.auth {
+            manifest_request = manifest_request.basic_auth(&auth.0, auth.1.as_ref());
+        }
+
+        manifest_request
+    }).await?.error_for_status()?.json().await?;
+
+    if let Some(config) = manifest.config {
+        let blob_url = registry.registry_url.join(&format!("/v2/{repository}/blobs/{}", config.digest))?;
+
+        let image_config: ImageConfig = send_with_retry(&registry.semaphore, registry.max_retries, || {
+            let mut blob_request = registry.client.get(blob_url.clone()).headers(registry.headers.clone());
+
+            if let Some(auth) = &registry.auth {
+                blob_request = blob_request.basic_auth(&auth.0, auth.1.as_ref());
+            }
+
+            blob_request
+        }).await?.error_for_status()?.json().await?;
+
+        return Ok(image_config.created.and_then(|created| parse_rfc3339(&created)));
+    }
+
+    let v1_manifest: ManifestV1 = send_with_retry(&registry.semaphore, registry.max_retries, || {
+        let mut v1_manifest_request = registry.client.get(manifest_url.clone())
+            .header(ACCEPT, HeaderValue::from_static("application/vnd.docker.distribution.manifest.v1+json"));
+
+        if let Some(auth) = &registry.auth {
+            v1_manifest_request = v1_manifest_request.basic_auth(&auth.0, auth.1.as_ref());
+        }
+
+        v1_manifest_request
+    }).await?.error_for_status()?.json().await?;
+
+    let latest = v1_manifest.history.unwrap_or_default()
+        .into_iter()
+        .filter_map(|entry| serde_json::from_str::<V1Compatibility>(&entry.v1_compatibility).ok())
+        .filter_map(|compat| compat.created)
+        .filter_map(|created| parse_rfc3339(&created))
+        .max();
+
+    Ok(latest)
+}
+
+fn parse_rfc3339(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(value).ok().map(|dt| dt.with_timezone(&Utc))
+}
+
 /// Returns the pair of (tags_to_keep, tags_to_remove)
-/// Will sort things in descending order. If semver == true, it'll use semantic versioning to order
-/// the things. Otherwise it'll sort lexicographically.
-pub fn classify_tags(mut tags: Vec<String>, num_tags: usize, semver: bool) -> (Vec<String>, Vec<String>) {
+/// Will sort things in descending order according to `sort`. `created` is only consulted for
+/// `SortMode::Date` and should map tag name to its resolved creation time, if any; tags with no
+/// resolvable timestamp sort last so they're never silently kept or deleted by chance of order.
+pub fn classify_tags(
+    mut tags: Vec<String>,
+    num_tags: usize,
+    sort: SortMode,
+    created: &HashMap<String, Option<DateTime<Utc>>>,
+) -> (Vec<String>, Vec<String>) {
     let n = num_tags.min(tags.len());
 
-    let sorted = if semver {
-        let mut versions: Vec<(Version, String)> = tags
-            .into_iter()
-            .filter_map(|tag|{
-                let vstr = tag.trim_start_matches('v');
-                Version::parse(vstr).ok().map(|ver| (ver, tag))
-            })
-        .collect();
-
-        versions.sort_unstable_by(|(a, _), (b, _)| b.cmp(a));
-        versions.into_iter().map(|v| v.1).collect()
-    } else {
-        tags.sort_unstable_by(|a, b| b.cmp(a));
+    let sorted = match sort {
+        SortMode::Semver => {
+            let mut versions: Vec<(Version, String)> = tags
+                .into_iter()
+                .filter_map(|tag|{
+                    let vstr = tag.trim_start_matches('v');
+                    Version::parse(vstr).ok().map(|ver| (ver, tag))
+                })
+            .collect();
+
+            versions.sort_unstable_by(|(a, _), (b, _)| b.cmp(a));
+            versions.into_iter().map(|v| v.1).collect()
+        }
+        SortMode::Lex => {
+            tags.sort_unstable_by(|a, b| b.cmp(a));
+
+            tags
+        }
+        SortMode::Date => {
+            tags.sort_by(|a, b| {
+                let a_created = created.get(a).copied().flatten();
+                let b_created = created.get(b).copied().flatten();
+
+                match (a_created, b_created) {
+                    (Some(a), Some(b)) => b.cmp(&a),
+                    (Some(_), None) => std::cmp::Ordering::Less,
+                    (None, Some(_)) => std::cmp::Ordering::Greater,
+                    (None, None) => std::cmp::Ordering::Equal,
+                }
+            });
 
-        tags
+            tags
+        }
     };
 
     let tags_to_keep = sorted[..n].to_vec();
@@ -224,56 +521,236 @@ async fn main() -> Result<(), BoxError> {
         }
     };
 
-    let client = Client::new();
+    let regex_exclude_tags: Result<Vec<(String, Regex)>, regex::Error> = args.exclude_tags.iter().map(|t| Regex::new(t).map(|r| (t.clone(), r))).collect();
+    let regex_exclude_tags = match regex_exclude_tags {
+        Ok(v) => v,
+        Err(e) => {
+            return Err(format!("Invalid exclude-tag regex: {e}").into());
+        }
+    };
+
+    let registry = RegistryClient {
+        client: Client::new(),
+        registry_url: args.registry_url.clone(),
+        headers,
+        auth,
+        semaphore: Arc::new(Semaphore::new(args.concurrency.get())),
+        max_retries: args.max_retries,
+    };
 
-    let catalog_data: Catalog = get_catalogs(&client, &args.registry_url, &headers, auth.as_ref()).await?;
+    let catalog_data: Catalog = get_catalogs(&registry, args.page_size).await?;
 
-    let mut join_set: JoinSet<Result<(Vec<String>, usize), BoxError>> = JoinSet::new();
+    let mut join_set: JoinSet<RepoResult> = JoinSet::new();
 
     for repository in catalog_data.repositories {
         if !regex_images.is_empty() && !regex_images.iter().any(|regexp| regexp.1.is_match(&repository)) {
-            println!("Image doesn't match any of the images specified.");
+            if args.format == OutputFormat::Text {
+                println!("Image doesn't match any of the images specified.");
+            }
             continue;
         }
 
-        let client = client.clone();
-        let registry_url = args.registry_url.clone();
-        let headers = headers.clone();
-        let auth = auth.clone();
+        let registry = registry.clone();
         let regex_tags = regex_tags.clone();
+        let sort = args.sort;
+        let min_age = args.min_age;
+        let page_size = args.page_size;
+        let exclude_tags = regex_exclude_tags.clone();
 
         join_set.spawn(async move {
-            let tag_list = get_tag_list(&client, &registry_url, &headers, auth.as_ref(), &repository).await?;
+            let tag_list = get_tag_list(&registry, &repository, page_size).await?;
             let matching_tags = get_matching_tags(&tag_list, &regex_tags);
 
             let mut log: Vec<String> = Vec::new();
+            let mut records: Vec<Record> = Vec::new();
             let mut tags_for_deletion = 0;
 
             if matching_tags.is_empty() {
                 log.push(format!("[{repository}] No tags eligable for deletion found."));
             } else {
+                let mut pattern_results: Vec<(String, Vec<String>, Vec<String>, HashSet<String>)> = Vec::new();
+
                 for t in matching_tags.into_iter() {
+                    let needs_created = sort == SortMode::Date || min_age.is_some();
+
+                    let mut created: HashMap<String, Option<DateTime<Utc>>> = HashMap::new();
+                    if needs_created {
+                        for tag in t.1.iter() {
+                            let created_at = get_tag_created(&registry, &repository, tag).await?;
+
+                            if created_at.is_none() {
+                                log.push(format!("[{repository}] WARNING: Couldn't resolve creation time for tag {tag}, treating it as the oldest"));
+                            }
+
+                            created.insert(tag.clone(), created_at);
+                        }
+                    }
+
                     // TODO: Make testable?
-                    // TODO: Decide sort order?
                     // TODO: Allow specifying ways to sort? Kinda like how it's done by flux image policies?
-                    let (_tags_to_keep, tags_to_remove) = classify_tags(t.1, args.max_per_tag.into(), args.semver);
+                    let (mut tags_to_keep, tags_to_remove) = classify_tags(t.1, args.max_per_tag.into(), sort, &created);
+
+                    // Tags protected below (by --min-age or --exclude-tag) are folded back into
+                    // tags_to_keep, not just logged, so the shared-digest guard further down
+                    // resolves their digest too and won't let a tag sharing one slip through
+                    // under a different pattern.
+                    let mut protected_tags: HashSet<String> = HashSet::new();
+
+                    let tags_to_remove = if let Some(min_age) = min_age {
+                        let cutoff = Utc::now() - chrono::Duration::from_std(min_age.into())?;
+
+                        tags_to_remove.into_iter().filter(|tag| {
+                            match created.get(tag).copied().flatten() {
+                                Some(created_at) if created_at > cutoff => {
+                                    let reason = format!("created {created_at}, newer than --min-age");
+                                    log.push(format!("[{repository}] protected {tag} ({reason})"));
+                                    records.push(Record {
+                                        repository: repository.clone(),
+                                        pattern: t.0.clone(),
+                                        tag: tag.clone(),
+                                        digest: None,
+                                        action: Action::Protected,
+                                        reason,
+                                        deleted: false,
+                                    });
+                                    protected_tags.insert(tag.clone());
+                                    tags_to_keep.push(tag.clone());
+                                    false
+                                }
+                                _ => true,
+                            }
+                        }).collect()
+                    } else {
+                        tags_to_remove
+                    };
+
+                    // Exclude patterns win over anything classify_tags decided: a matching tag
+                    // is pulled back into the kept set so its digest is resolved and protected by
+                    // the shared-digest guard below, same as any other kept tag.
+                    let (tags_to_remove, excluded_tags): (Vec<String>, Vec<String>) = tags_to_remove
+                        .into_iter()
+                        .partition(|tag| !exclude_tags.iter().any(|(_, regex)| regex.is_match(tag)));
+
+                    for tag in excluded_tags {
+                        if let Some((pattern, _)) = exclude_tags.iter().find(|(_, regex)| regex.is_match(&tag)) {
+                            let reason = format!("matched exclude /{pattern}/");
+                            log.push(format!("[{repository}] protected {tag} ({reason})"));
+                            records.push(Record {
+                                repository: repository.clone(),
+                                pattern: t.0.clone(),
+                                tag: tag.clone(),
+                                digest: None,
+                                action: Action::Protected,
+                                reason,
+                                deleted: false,
+                            });
+                        }
+
+                        protected_tags.insert(tag.clone());
+                        tags_to_keep.push(tag);
+                    }
+
+                    pattern_results.push((t.0, tags_to_keep, tags_to_remove, protected_tags));
+                }
 
+                // The registry deletes by digest, not by tag, so a tag marked for removal
+                // under one pattern can share a digest with a tag kept under another (e.g.
+                // `latest` aliasing `1.2.3`), or with a tag protected by --min-age/--exclude-tag.
+                // Resolve every kept/protected tag's digest up front so we never delete one out
+                // from under a tag we decided to keep.
+                let mut kept_digests: HashMap<String, String> = HashMap::new();
+                for (pattern, tags_to_keep, _, protected_tags) in &pattern_results {
+                    for tag in tags_to_keep {
+                        let digest = get_tag_digest(&registry, &repository, tag).await?;
+
+                        if let Some(digest) = &digest {
+                            kept_digests.entry(digest.clone()).or_insert_with(|| tag.clone());
+                        }
+
+                        // Protected tags already got an Action::Protected record with their own
+                        // reason above; don't also emit a Keep record for them here.
+                        if !protected_tags.contains(tag) {
+                            records.push(Record {
+                                repository: repository.clone(),
+                                pattern: pattern.clone(),
+                                tag: tag.clone(),
+                                digest,
+                                action: Action::Keep,
+                                reason: "within --max-per-tag retention".to_string(),
+                                deleted: false,
+                            });
+                        }
+                    }
+                }
+
+                let mut deleted_digests: HashSet<String> = HashSet::new();
+
+                for (pattern, _tags_to_keep, tags_to_remove, _protected_tags) in pattern_results {
                     if !tags_to_remove.is_empty() {
-                        tags_for_deletion += tags_to_remove.len();
-                        log.push(format!("[{repository}] Found {} tags eligable for deletion for pattern /{}/", tags_to_remove.len(), t.0));
+                        log.push(format!("[{repository}] Found {} tags eligable for deletion for pattern /{}/", tags_to_remove.len(), pattern));
 
                         for tag_to_remove in tags_to_remove {
-                            let tag_digest = get_tag_digest(&client, &registry_url, &headers, auth.as_ref(), &repository, &tag_to_remove).await?;
+                            let tag_digest = get_tag_digest(&registry, &repository, &tag_to_remove).await?;
 
                             if let Some(tag_digest) = tag_digest {
+                                if let Some(kept_tag) = kept_digests.get(&tag_digest) {
+                                    let reason = format!("digest shared with kept tag {kept_tag}");
+                                    log.push(format!("[{repository}] SKIPPED {tag_to_remove} ({reason})"));
+                                    records.push(Record {
+                                        repository: repository.clone(),
+                                        pattern: pattern.clone(),
+                                        tag: tag_to_remove,
+                                        digest: Some(tag_digest),
+                                        action: Action::Skipped,
+                                        reason,
+                                        deleted: false,
+                                    });
+                                    continue;
+                                }
+
+                                if !deleted_digests.insert(tag_digest.clone()) {
+                                    let reason = format!("digest {tag_digest} already handled earlier this run");
+                                    log.push(format!("[{repository}] SKIPPED {tag_to_remove} ({reason})"));
+                                    records.push(Record {
+                                        repository: repository.clone(),
+                                        pattern: pattern.clone(),
+                                        tag: tag_to_remove,
+                                        digest: Some(tag_digest),
+                                        action: Action::Skipped,
+                                        reason,
+                                        deleted: false,
+                                    });
+                                    continue;
+                                }
+
                                 log.push(format!("[{repository}] tag to be deleted {tag_to_remove}"));
 
                                 if args.delete {
-                                    delete_tag(&client, &registry_url, &headers, auth.as_ref(), &repository, &tag_digest).await?;
+                                    delete_tag(&registry, &repository, &tag_digest).await?;
                                     log.push(format!("[{repository}] Deleted {tag_to_remove}"))
                                 }
+
+                                tags_for_deletion += 1;
+                                records.push(Record {
+                                    repository: repository.clone(),
+                                    pattern: pattern.clone(),
+                                    tag: tag_to_remove,
+                                    digest: Some(tag_digest),
+                                    action: Action::Delete,
+                                    reason: "exceeds --max-per-tag retention".to_string(),
+                                    deleted: args.delete,
+                                });
                             } else {
                                 log.push(format!("[{repository}] WARNING: Couldn't find tag digest for {tag_to_remove}"));
+                                records.push(Record {
+                                    repository: repository.clone(),
+                                    pattern: pattern.clone(),
+                                    tag: tag_to_remove,
+                                    digest: None,
+                                    action: Action::Skipped,
+                                    reason: "couldn't find tag digest".to_string(),
+                                    deleted: false,
+                                });
                             }
                         }
                     }
@@ -284,35 +761,61 @@ async fn main() -> Result<(), BoxError> {
                 }
             }
 
-            Ok((log, tags_for_deletion))
+            Ok((log, records, tags_for_deletion))
         });
     }
 
     let mut num_tags_to_delete: usize = 0;
     let mut errors = Vec::new();
+    let mut all_records: Vec<Record> = Vec::new();
 
     while let Some(thread_result) = join_set.join_next().await {
         match thread_result? {
-            Ok((log, n)) => {
-                println!("{}", log.join("\n"));
+            Ok((log, records, n)) => {
+                match args.format {
+                    OutputFormat::Text => println!("{}", log.join("\n")),
+                    OutputFormat::Ndjson => {
+                        for record in &records {
+                            println!("{}", serde_json::to_string(record)?);
+                        }
+                    }
+                    OutputFormat::Json => {}
+                }
+
                 num_tags_to_delete += n;
+                all_records.extend(records);
             },
             Err(e) => errors.push(e),
         }
     }
 
-    if !errors.is_empty() {
-        println!("The following errors occured during processing:\n\t{}\n", errors.into_iter().map(|e| format!("{e}")).collect::<Vec<String>>().join("\n\t"));
-    }
+    let summary = Summary {
+        total_tags_to_delete: num_tags_to_delete,
+        delete: args.delete,
+        errors: errors.into_iter().map(|e| format!("{e}")).collect(),
+    };
 
-    if args.delete {
-        println!("\n\tDeleted a total of {num_tags_to_delete} tag(s)");
-        println!("\n\tRemember to run garbage collection on your registry to ensure that files get removed on disk.");
-    } else {
-        println!("\n\tFound a total of {num_tags_to_delete} tag(s) to delete");
-        println!("\n\tDelete flag (-d/--delete) not specified, none of the above have actually been deleted.");
+    match args.format {
+        OutputFormat::Text => {
+            if !summary.errors.is_empty() {
+                println!("The following errors occured during processing:\n\t{}\n", summary.errors.join("\n\t"));
+            }
+
+            if args.delete {
+                println!("\n\tDeleted a total of {num_tags_to_delete} tag(s)");
+                println!("\n\tRemember to run garbage collection on your registry to ensure that files get removed on disk.");
+            } else {
+                println!("\n\tFound a total of {num_tags_to_delete} tag(s) to delete");
+                println!("\n\tDelete flag (-d/--delete) not specified, none of the above have actually been deleted.");
+            }
+        }
+        OutputFormat::Ndjson => {
+            println!("{}", serde_json::to_string(&summary)?);
+        }
+        OutputFormat::Json => {
+            println!("{}", serde_json::to_string_pretty(&Report { records: all_records, summary })?);
+        }
     }
 
     Ok(())
 }
-